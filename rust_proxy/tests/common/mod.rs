@@ -0,0 +1,59 @@
+//! Shared helpers for the integration tests: run the proxy in-process on a
+//! free port in front of a mock upstream.
+
+#![allow(dead_code)] // each test binary uses a different subset
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// A port nothing is listening on (at the time of the call)
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
+
+/// Serve `app` on an ephemeral port as the mock upstream
+pub async fn start_upstream(app: axum::Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    addr
+}
+
+/// Start the proxy in --no-ssl mode in front of `upstream_port` and wait
+/// until it accepts connections. `extra` is appended to the command line.
+pub async fn start_proxy(upstream_port: u16, extra: &[&str]) -> SocketAddr {
+    let port = free_port();
+    let port_arg = port.to_string();
+    let upstream_arg = upstream_port.to_string();
+    let mut argv = vec![
+        "rust_proxy",
+        "--no-ssl",
+        "--port",
+        &port_arg,
+        "--upstream-port",
+        &upstream_arg,
+        "--health-interval-secs",
+        "0",
+    ];
+    argv.extend_from_slice(extra);
+    let config = rust_proxy::ProxyConfig::try_parse_from(argv).expect("invalid proxy arguments");
+    tokio::spawn(async move {
+        if let Err(e) = rust_proxy::run(config).await {
+            panic!("proxy failed: {}", e);
+        }
+    });
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_ok() {
+            return addr;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("proxy did not start listening on {}", addr);
+}
@@ -0,0 +1,367 @@
+//! End-to-end tests of the HTTP proxy path against a mock upstream
+
+mod common;
+
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::any;
+use axum::Router;
+
+/// Mock upstream that reports the request headers it received, one `name: value` per line
+async fn echo_headers(headers: HeaderMap) -> impl IntoResponse {
+    let body: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value.to_str().unwrap_or_default()))
+        .collect();
+    (
+        [("x-upstream-hop", "secret"), ("connection", "x-upstream-hop"), ("x-upstream", "kept")],
+        body,
+    )
+}
+
+fn received_header<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.lines()
+        .find_map(|line| line.split_once(": ").filter(|(n, _)| *n == name).map(|(_, v)| v))
+}
+
+async fn proxy_in_front_of_echo() -> (std::net::SocketAddr, std::net::SocketAddr) {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy = common::start_proxy(upstream.port(), &[]).await;
+    (proxy, upstream)
+}
+
+#[tokio::test]
+async fn forwards_request_with_forwarding_headers() {
+    let (proxy, upstream) = proxy_in_front_of_echo().await;
+
+    let response = reqwest::get(format!("http://{}/some/path?q=1", proxy)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+
+    assert_eq!(received_header(&body, "x-forwarded-for"), Some("127.0.0.1"));
+    assert_eq!(received_header(&body, "x-real-ip"), Some("127.0.0.1"));
+    assert_eq!(received_header(&body, "x-forwarded-proto"), Some("http"));
+    assert_eq!(received_header(&body, "host"), Some(upstream.to_string().as_str()));
+    assert_eq!(received_header(&body, "x-forwarded-host"), Some(proxy.to_string().as_str()));
+    assert!(received_header(&body, "x-request-id").is_some());
+}
+
+#[tokio::test]
+async fn appends_to_trusted_forwarded_for_chain() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy = common::start_proxy(upstream.port(), &["--trusted-proxies", "127.0.0.0/8"]).await;
+
+    let body = reqwest::Client::new()
+        .get(format!("http://{}/x", proxy))
+        .header("x-forwarded-for", "203.0.113.7")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert_eq!(received_header(&body, "x-forwarded-for"), Some("203.0.113.7, 127.0.0.1"));
+    assert_eq!(received_header(&body, "x-real-ip"), Some("203.0.113.7"));
+}
+
+#[tokio::test]
+async fn strips_hop_by_hop_headers_both_ways() {
+    let (proxy, _) = proxy_in_front_of_echo().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("http://{}/x", proxy))
+        .header("connection", "x-client-hop")
+        .header("x-client-hop", "secret")
+        .header("proxy-authorization", "Basic Zm9vOmJhcg==")
+        .header("x-client", "kept")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers().get("x-upstream").unwrap(), "kept");
+    assert!(response.headers().get("x-upstream-hop").is_none());
+
+    let body = response.text().await.unwrap();
+    assert_eq!(received_header(&body, "x-client"), Some("kept"));
+    assert_eq!(received_header(&body, "x-client-hop"), None);
+    assert_eq!(received_header(&body, "proxy-authorization"), None);
+    assert_eq!(received_header(&body, "connection"), None);
+}
+
+#[tokio::test]
+async fn adds_security_headers() {
+    let (proxy, _) = proxy_in_front_of_echo().await;
+
+    let response = reqwest::get(format!("http://{}/x", proxy)).await.unwrap();
+    let headers = response.headers();
+    assert_eq!(headers.get("x-content-type-options").unwrap(), "nosniff");
+    assert_eq!(headers.get("x-frame-options").unwrap(), "SAMEORIGIN");
+    assert_eq!(headers.get("referrer-policy").unwrap(), "strict-origin-when-cross-origin");
+    // HSTS is only meaningful over TLS, and this listener is plain HTTP
+    assert!(headers.get("strict-transport-security").is_none());
+    assert!(headers.get("x-request-id").is_some());
+}
+
+#[tokio::test]
+async fn response_headers_are_stripped_and_set() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy = common::start_proxy(
+        upstream.port(),
+        &[
+            "--strip-response-header",
+            "X-Upstream",
+            "--set-response-header",
+            "x-robots-tag=noindex",
+            "--set-response-header",
+            "X-Frame-Options=DENY",
+        ],
+    )
+    .await;
+
+    let response = reqwest::get(format!("http://{}/x", proxy)).await.unwrap();
+    let headers = response.headers();
+    assert!(headers.get("x-upstream").is_none());
+    assert_eq!(headers.get("x-robots-tag").unwrap(), "noindex");
+    assert_eq!(headers.get("x-frame-options").unwrap(), "DENY");
+}
+
+#[tokio::test]
+async fn request_headers_are_stripped_and_set() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy = common::start_proxy(
+        upstream.port(),
+        &["--strip-request-header", "X-Debug", "--set-request-header", "x-internal-user=proxy"],
+    )
+    .await;
+
+    let body = reqwest::Client::new()
+        .get(format!("http://{}/x", proxy))
+        .header("x-debug", "1")
+        .header("x-internal-user", "admin")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(received_header(&body, "x-debug"), None);
+    assert_eq!(received_header(&body, "x-internal-user"), Some("proxy"));
+}
+
+#[tokio::test]
+async fn head_keeps_content_length_without_a_body() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = common::start_upstream(Router::new().route("/file", any(|| async { "0123456789" }))).await;
+    let proxy = common::start_proxy(upstream.port(), &[]).await;
+
+    // Raw socket, so a body sent after the headers would show up
+    let mut stream = tokio::net::TcpStream::connect(proxy).await.unwrap();
+    stream
+        .write_all(b"HEAD /file HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).await.unwrap();
+
+    let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(head.lines().any(|line| line == "content-length: 10"), "{}", head);
+    assert!(body.is_empty(), "HEAD response carried a body: {:?}", body);
+}
+
+#[tokio::test]
+async fn path_prefix_is_stripped_and_added() {
+    let upstream = common::start_upstream(Router::new().route(
+        "/{*path}",
+        any(|uri: axum::http::Uri| async move { uri.to_string() }),
+    ))
+    .await;
+    let proxy = common::start_proxy(upstream.port(), &["--strip-prefix", "/public/", "--add-prefix", "/app"]).await;
+
+    let get = |path: &str| {
+        let url = format!("http://{}{}", proxy, path);
+        async move { reqwest::get(url).await.unwrap().text().await.unwrap() }
+    };
+    assert_eq!(get("/public/index.html?v=2").await, "/app/index.html?v=2");
+    assert_eq!(get("/public").await, "/app/");
+    assert_eq!(get("/publicity").await, "/app/publicity");
+}
+
+#[tokio::test]
+async fn upstream_down_is_bad_gateway() {
+    let proxy = common::start_proxy(common::free_port(), &[]).await;
+
+    let response = reqwest::get(format!("http://{}/x", proxy)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response.headers().get("x-content-type-options").unwrap(), "nosniff");
+}
+
+#[tokio::test]
+async fn proxy_health_without_health_checks_does_not_claim_reachability() {
+    // start_proxy passes --health-interval-secs 0
+    let proxy = common::start_proxy(common::free_port(), &[]).await;
+
+    let response = reqwest::get(format!("http://{}/__proxy_health", proxy)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), r#"{"status":"ok","upstream_reachable":null}"#);
+}
+
+#[tokio::test]
+async fn upstream_dropping_mid_body_is_not_a_clean_end() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Promises more body than it sends, then hangs up
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\npartial").await;
+        }
+    });
+    let proxy = common::start_proxy(upstream_port, &[]).await;
+
+    let response = reqwest::get(format!("http://{}/big", proxy)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.bytes().await.is_err(), "truncated body read as complete");
+}
+
+#[tokio::test]
+async fn maintenance_mode_is_toggled_by_the_admin_api() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy = common::start_proxy(upstream.port(), &["--maintenance", "--admin-token", "t0ken"]).await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("http://{}/x", proxy)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers().get("retry-after").unwrap(), "5");
+
+    let toggle = |path: &'static str, token: &'static str| {
+        client.post(format!("http://{}{}", proxy, path)).bearer_auth(token).send()
+    };
+    let response = toggle("/__admin/maintenance/off", "wrong").await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = toggle("/__admin/maintenance/off", "t0ken").await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "{\"maintenance\":false}");
+
+    let response = client.get(format!("http://{}/x", proxy)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admin_api_obeys_the_access_list_and_rate_limit() {
+    let client = reqwest::Client::new();
+    let post = |proxy: std::net::SocketAddr, token: &'static str| {
+        client.post(format!("http://{}/__admin/maintenance/on", proxy)).bearer_auth(token).send()
+    };
+
+    let denied = common::start_proxy(common::free_port(), &["--admin-token", "t0ken", "--deny-cidr", "127.0.0.0/8"]).await;
+    assert_eq!(post(denied, "t0ken").await.unwrap().status(), StatusCode::FORBIDDEN);
+
+    let limited =
+        common::start_proxy(common::free_port(), &["--admin-token", "t0ken", "--rate-limit", "0.1", "--rate-burst", "1"]).await;
+    assert_eq!(post(limited, "guess").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(post(limited, "t0ken").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn per_ip_limit_sends_retry_after() {
+    let (release, released) = tokio::sync::watch::channel(false);
+    let hold = move || {
+        let mut released = released.clone();
+        async move {
+            let _ = released.wait_for(|done| *done).await;
+            "done"
+        }
+    };
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(hold))).await;
+    let proxy = common::start_proxy(upstream.port(), &["--max-connections-per-ip", "1"]).await;
+
+    let held = tokio::spawn(reqwest::get(format!("http://{}/held", proxy)));
+    let mut response = None;
+    for _ in 0..50 {
+        let r = reqwest::get(format!("http://{}/second", proxy)).await.unwrap();
+        if r.status() == StatusCode::TOO_MANY_REQUESTS {
+            response = Some(r);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let response = response.expect("second request was never limited");
+    assert_eq!(response.headers().get("retry-after").unwrap(), "5");
+
+    release.send_replace(true);
+    assert_eq!(held.await.unwrap().unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn maintenance_allow_cidr_bypasses_maintenance() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy =
+        common::start_proxy(upstream.port(), &["--maintenance", "--maintenance-allow-cidr", "127.0.0.0/8"]).await;
+
+    let response = reqwest::get(format!("http://{}/x", proxy)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn expose_upstream_errors_names_the_failed_target() {
+    let upstream_port = common::free_port();
+    let proxy = common::start_proxy(upstream_port, &["--expose-upstream-errors"]).await;
+
+    let response = reqwest::get(format!("http://{}/x?y=1", proxy)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let body = response.text().await.unwrap();
+    assert!(body.contains(&format!("upstream: http://127.0.0.1:{}/x?y=1", upstream_port)), "{}", body);
+    assert!(body.contains("error_kind: connection_refused"), "{}", body);
+}
+
+#[tokio::test]
+async fn expect_continue_is_answered_only_when_the_body_is_wanted() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = common::start_upstream(Router::new().route(
+        "/{*path}",
+        any(|body: axum::body::Bytes| async move { format!("got {}", body.len()) }),
+    ))
+    .await;
+    let proxy = common::start_proxy(upstream.port(), &[]).await;
+    let guarded = common::start_proxy(upstream.port(), &["--basic-auth", "user:pass"]).await;
+    let head = b"PUT /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n";
+
+    let mut stream = tokio::net::TcpStream::connect(proxy).await.unwrap();
+    stream.write_all(head).await.unwrap();
+    let mut buf = vec![0u8; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 100 Continue\r\n"), "{}", String::from_utf8_lossy(&buf[..n]));
+    stream.write_all(b"hello").await.unwrap();
+    let mut rest = Vec::new();
+    while !String::from_utf8_lossy(&rest).contains("got 5") {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed: {}", String::from_utf8_lossy(&rest));
+        rest.extend_from_slice(&buf[..n]);
+    }
+    assert!(String::from_utf8_lossy(&rest).contains("HTTP/1.1 200"), "{}", String::from_utf8_lossy(&rest));
+
+    // Refused before forwarding: the final status comes without a 100 first
+    let mut stream = tokio::net::TcpStream::connect(guarded).await.unwrap();
+    stream.write_all(head).await.unwrap();
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 401"), "{}", String::from_utf8_lossy(&buf[..n]));
+}
+
+#[tokio::test]
+async fn propagate_deadline_sends_the_proxy_timeout() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo_headers))).await;
+    let proxy = common::start_proxy(
+        upstream.port(),
+        &["--propagate-deadline", "--deadline-header", "grpc-timeout", "--path-timeout", "/slow=90"],
+    )
+    .await;
+
+    let body = reqwest::get(format!("http://{}/slow/job", proxy)).await.unwrap().text().await.unwrap();
+    assert_eq!(received_header(&body, "grpc-timeout"), Some("90000m"));
+}
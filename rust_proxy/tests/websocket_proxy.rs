@@ -0,0 +1,278 @@
+//! End-to-end tests of WebSocket proxying against a mock echo upstream
+
+mod common;
+
+use std::time::Duration;
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::response::Response;
+use axum::routing::any;
+use axum::Router;
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+/// Echo text and binary messages back. The text `close:<code>:<reason>`
+/// makes the upstream close the session with that code and reason instead.
+async fn echo(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(|mut socket: WebSocket| async move {
+        while let Some(Ok(msg)) = socket.recv().await {
+            let reply = match msg {
+                Message::Text(text) => match text.strip_prefix("close:").and_then(|rest| rest.split_once(':')) {
+                    Some((code, reason)) => Message::Close(Some(CloseFrame {
+                        code: code.parse().unwrap(),
+                        reason: reason.to_string().into(),
+                    })),
+                    None => Message::Text(text),
+                },
+                Message::Binary(data) => Message::Binary(data),
+                // Pings are answered by the WebSocket library itself
+                Message::Ping(_) | Message::Pong(_) => continue,
+                Message::Close(_) => break,
+            };
+            let closing = matches!(reply, Message::Close(_));
+            if socket.send(reply).await.is_err() || closing {
+                break;
+            }
+        }
+    })
+}
+
+type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn connect() -> Client {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(echo))).await;
+    let proxy = common::start_proxy(upstream.port(), &["--ws-keepalive-secs", "0"]).await;
+    let (client, response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    assert_eq!(response.status(), 101);
+    client
+}
+
+/// Next message other than a pong (pings get answered by both the proxy and the upstream)
+async fn next_message(client: &mut Client) -> ClientMessage {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("stream ended")
+            .expect("WebSocket error");
+        if !matches!(msg, ClientMessage::Pong(_)) {
+            return msg;
+        }
+    }
+}
+
+#[tokio::test]
+async fn text_and_binary_round_trip() {
+    let mut client = connect().await;
+
+    client.send(ClientMessage::text("hello")).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text("hello"));
+
+    let payload = vec![0u8, 159, 146, 150, 255];
+    client.send(ClientMessage::binary(payload.clone())).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::binary(payload));
+}
+
+#[tokio::test]
+async fn ping_is_answered_with_matching_pong() {
+    let mut client = connect().await;
+
+    client.send(ClientMessage::Ping(b"are you there".to_vec().into())).await.unwrap();
+    let pong = tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(pong, ClientMessage::Pong(b"are you there".to_vec().into()));
+
+    // The session keeps forwarding after control frames
+    client.send(ClientMessage::text("still here")).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text("still here"));
+}
+
+#[tokio::test]
+async fn upstream_close_code_and_reason_reach_client() {
+    let mut client = connect().await;
+
+    client.send(ClientMessage::text("close:4002:server says bye")).await.unwrap();
+    match next_message(&mut client).await {
+        ClientMessage::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::from(4002));
+            assert_eq!(frame.reason, "server says bye");
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn client_close_is_acknowledged() {
+    let mut client = connect().await;
+
+    client
+        .close(Some(tokio_tungstenite::tungstenite::protocol::CloseFrame {
+            code: CloseCode::from(4001),
+            reason: "client says bye".into(),
+        }))
+        .await
+        .unwrap();
+    match next_message(&mut client).await {
+        ClientMessage::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::from(4001));
+            assert_eq!(frame.reason, "client says bye");
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+/// Upstream that greets each session with the path and query it was opened on
+async fn report_path(ws: WebSocketUpgrade, uri: axum::http::Uri) -> Response {
+    ws.on_upgrade(move |mut socket: WebSocket| async move {
+        let _ = socket.send(Message::Text(uri.to_string().into())).await;
+    })
+}
+
+#[tokio::test]
+async fn path_prefix_is_rewritten_for_the_upstream() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(report_path))).await;
+    let proxy = common::start_proxy(
+        upstream.port(),
+        &["--ws-keepalive-secs", "0", "--strip-prefix", "/public", "--add-prefix", "/app"],
+    )
+    .await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/public/terminal/ws?id=7", proxy))
+        .await
+        .unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text("/app/terminal/ws?id=7"));
+
+    // Outside the prefix, without --strip-prefix-strict: only --add-prefix applies
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text("/app/ws"));
+}
+
+/// Upstream that sends the request's Authorization and X-Tenant headers as its first message
+async fn report_headers(ws: WebSocketUpgrade, headers: axum::http::HeaderMap) -> Response {
+    let value = |name| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+    let report = format!("{} {}", value("authorization"), value("x-tenant"));
+    ws.on_upgrade(move |mut socket: WebSocket| async move {
+        let _ = socket.send(Message::Text(report.into())).await;
+    })
+}
+
+#[tokio::test]
+async fn request_header_rules_apply_to_websockets() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(report_headers))).await;
+    let proxy = common::start_proxy(
+        upstream.port(),
+        &[
+            "--ws-keepalive-secs",
+            "0",
+            "--strip-request-header",
+            "authorization",
+            "--set-request-header",
+            "X-Tenant=blue",
+        ],
+    )
+    .await;
+
+    let mut request = format!("ws://{}/ws", proxy).into_client_request().unwrap();
+    request.headers_mut().insert("authorization", "Bearer spoofed".parse().unwrap());
+    let (mut client, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text("- blue"));
+}
+
+async fn report_host(ws: WebSocketUpgrade, headers: axum::http::HeaderMap) -> Response {
+    let value = |name| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+    let report = format!("{} {}", value("host"), value("x-forwarded-host"));
+    ws.on_upgrade(move |mut socket: WebSocket| async move {
+        let _ = socket.send(Message::Text(report.into())).await;
+    })
+}
+
+#[tokio::test]
+async fn websocket_host_follows_preserve_host() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(report_host))).await;
+
+    let proxy = common::start_proxy(upstream.port(), &["--ws-keepalive-secs", "0"]).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    let expected = format!("127.0.0.1:{} {}", upstream.port(), proxy);
+    assert_eq!(next_message(&mut client).await, ClientMessage::text(expected));
+
+    let proxy = common::start_proxy(upstream.port(), &["--ws-keepalive-secs", "0", "--preserve-host"]).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text(format!("{} {}", proxy, proxy)));
+
+    let proxy =
+        common::start_proxy(upstream.port(), &["--ws-keepalive-secs", "0", "--upstream-host-header", "app.internal"]).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text(format!("app.internal {}", proxy)));
+}
+
+#[tokio::test]
+async fn strict_strip_prefix_refuses_other_paths() {
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(report_path))).await;
+    let proxy = common::start_proxy(
+        upstream.port(),
+        &["--ws-keepalive-secs", "0", "--strip-prefix", "/public", "--strip-prefix-strict"],
+    )
+    .await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/public/ws", proxy)).await.unwrap();
+    assert_eq!(next_message(&mut client).await, ClientMessage::text("/ws"));
+
+    match tokio_tungstenite::connect_async(format!("ws://{}/publicity/ws", proxy)).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => assert_eq!(response.status(), 404),
+        other => panic!("expected a 404, got {:?}", other.map(|(_, response)| response.status())),
+    }
+}
+
+#[tokio::test]
+async fn stalled_upstream_handshake_closes_the_client() {
+    // Accepts the TCP connection and never answers the upgrade
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            held.push(socket);
+        }
+    });
+    let proxy =
+        common::start_proxy(upstream_port, &["--ws-keepalive-secs", "0", "--ws-connect-timeout-secs", "1"]).await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    match next_message(&mut client).await {
+        ClientMessage::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Again),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unreachable_upstream_closes_the_client() {
+    let proxy = common::start_proxy(common::free_port(), &["--ws-keepalive-secs", "0"]).await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    match next_message(&mut client).await {
+        ClientMessage::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Again);
+            assert_eq!(frame.reason, "Upstream unavailable");
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn upstream_refusing_the_upgrade_closes_the_client() {
+    let refuse = || async { (axum::http::StatusCode::SERVICE_UNAVAILABLE, "maintenance") };
+    let upstream = common::start_upstream(Router::new().route("/{*path}", any(refuse))).await;
+    let proxy = common::start_proxy(upstream.port(), &["--ws-keepalive-secs", "0"]).await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", proxy)).await.unwrap();
+    match next_message(&mut client).await {
+        ClientMessage::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Error),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}